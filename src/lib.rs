@@ -1,11 +1,15 @@
-use std::{io, thread, string::String, io::Write};
+use std::{io, thread, string::String};
 use std::net::IpAddr;
-use serde_json::json;
+use std::time::Duration;
 use serde_derive::{Deserialize, Serialize};
 use mailin_embedded::{Handler, Response};
 use mailin_embedded::response::OK;
 use mail_parser::{MimeHeaders, MessageParser};
 use clap::Parser;
+use regex::Regex;
+
+mod telegram;
+use telegram::TelegramClient;
 
 #[derive(Clone, Debug)]
 struct Notification {
@@ -39,18 +43,11 @@ impl Notification {
             // Remove HTML from body
             full_text_body = nanohtml2text::html2text(
                 &nanohtml2text::html2text(&full_text_body) // FIXME?? lolol
-            ); 
-
-            // Cut body if greater than 4086 (4096 is max allowed)
-            if full_text_body.len() > 4086 {
-                full_text_body
-                    .char_indices()
-                    .take_while(|&(idx, _)| idx < 4086)
-                    .last()
-                    .map(|(idx, _)| idx)
-                    .unwrap_or(0);
-            }
+            );
 
+            // Note: the body is not truncated here. Telegram's 4096 UTF-16
+            // code unit limit is enforced by splitting it into several
+            // messages in send_to_telegram instead.
             full_text_body
         };
 
@@ -89,6 +86,41 @@ pub struct Account {
     address: String,
     telegram_bot_key: String,
     telegram_chat_id: String,
+
+    // How many times to retry a Telegram API call before giving up
+    #[serde(default = "default_telegram_max_attempts")]
+    pub telegram_max_attempts: u32,
+
+    // Base delay (seconds) for the exponential backoff used on 5xx/transport errors
+    #[serde(default = "default_telegram_backoff_base_secs")]
+    pub telegram_backoff_base_secs: u64,
+
+    // Extra recipient patterns this account should match, evaluated in order
+    // after `address`. Supports exact strings, globs (`*@mydomain.tld`) and
+    // anchored regexes (`/^sales-.*@mydomain\.tld$/`).
+    #[serde(default)]
+    pub match_patterns: Vec<String>,
+
+    // Attachments larger than this are skipped rather than uploaded
+    // (Telegram's bot API caps uploads at 50 MB)
+    #[serde(default = "default_telegram_max_attachment_bytes")]
+    pub telegram_max_attachment_bytes: u64,
+
+    // Receives mail that matches no account's `address`/`match_patterns`
+    #[serde(default)]
+    pub catch_all: bool,
+}
+
+fn default_telegram_max_attempts() -> u32 {
+    5
+}
+
+fn default_telegram_backoff_base_secs() -> u64 {
+    1
+}
+
+fn default_telegram_max_attachment_bytes() -> u64 {
+    50 * 1024 * 1024
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -103,12 +135,132 @@ pub struct SmtpServer {
     pub ca_path: String,
 }
 
+// What to do with a message once it has been relayed to telegram
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostFetchAction {
+    MarkSeen,
+    Delete,
+    Move,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ImapServer {
+    pub enabled: bool,
+    pub hostname: String,
+    pub port: u16,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+    pub mailbox: String,
+    pub poll_interval_secs: u64,
+    pub post_fetch_action: PostFetchAction,
+    // Target mailbox when `post_fetch_action` is `move`
+    #[serde(default)]
+    pub move_to: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ConfigFile {
     pub smtpservers: Vec<SmtpServer>,
+    #[serde(default)]
+    pub imapservers: Vec<ImapServer>,
     pub accounts: Vec<Account>,
 }
 
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    MissingEnvVar(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config file: {}", e),
+            ConfigError::Json(e) => write!(f, "failed to parse JSON config: {}", e),
+            ConfigError::Toml(e) => write!(f, "failed to parse TOML config: {}", e),
+            ConfigError::MissingEnvVar(name) => {
+                write!(f, "config references ${{{}}} but it is not set", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> ConfigError {
+        ConfigError::Json(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> ConfigError {
+        ConfigError::Toml(e)
+    }
+}
+
+// Reads and parses `path` as JSON or TOML (picked by file extension), then
+// expands `${ENV_VAR}` references in sensitive fields (telegram bot
+// key/chat id, TLS key path) so secrets can be injected at runtime instead
+// of committed in plaintext.
+pub fn load_config(path: &str) -> Result<ConfigFile, ConfigError> {
+    let content = std::fs::read_to_string(path)?;
+
+    let mut config = if path.to_lowercase().ends_with(".toml") {
+        toml::from_str::<ConfigFile>(&content)?
+    } else {
+        serde_json::from_str::<ConfigFile>(&content)?
+    };
+
+    for smtpserver in &mut config.smtpservers {
+        smtpserver.key_path = expand_env(&smtpserver.key_path)?;
+    }
+
+    for account in &mut config.accounts {
+        account.telegram_bot_key = expand_env(&account.telegram_bot_key)?;
+        account.telegram_chat_id = expand_env(&account.telegram_chat_id)?;
+    }
+
+    Ok(config)
+}
+
+// Expands every `${VAR}` reference in `value` against the process
+// environment, failing with the missing variable's name rather than
+// silently leaving an empty secret.
+fn expand_env(value: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let end = start + len;
+
+        expanded.push_str(&rest[..start]);
+
+        let var_name = &rest[start + 2..end];
+        let var_value = std::env::var(var_name)
+            .map_err(|_| ConfigError::MissingEnvVar(var_name.to_string()))?;
+        expanded.push_str(&var_value);
+
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
 #[derive(Parser)]
 pub struct Cli {
     // Sets a custom config file
@@ -188,98 +340,274 @@ impl Handler for MyHandler {
             // Get a Message
             let msg = Notification::new(&mime);
 
-            // Get destination account
-            let destination = find_account(&accounts, &msg.to);
-
-            // Send telegram message
-            send_to_telegram(
-                &msg,
-                &destination.telegram_chat_id,
-                &destination.telegram_bot_key,
-            )
+            // Get destination account and send telegram message
+            if let Some(destination) = find_account(&accounts, &msg.to) {
+                let _ = send_to_telegram(&msg, destination);
+            }
         });
 
         OK
     }
 }
 
+// Returns `Err(())` if any part of the notification (message text or an
+// attachment) failed to reach Telegram, so callers that delete/move the
+// source mail can skip doing so and let it be retried instead.
 fn send_to_telegram(
     message: &Notification,
-    chat_id: &str,
-    bot_key: &str,
-) {
-    // Telegram sendMessage API call URL
-    let telegram_message_url = format!(
-        "https://api.telegram.org/bot{}/sendMessage",
-        bot_key
+    account: &Account,
+) -> Result<(), ()> {
+    let mut delivered = true;
+
+    let client = TelegramClient::new(
+        account.telegram_bot_key.clone(),
+        account.telegram_max_attempts,
+        account.telegram_backoff_base_secs,
     );
-
-    // Telegram sendMessage API call URL
-    let telegram_document_url = format!(
-        "https://api.telegram.org/bot{}/sendDocument",
-        bot_key
+    let chat_id = &account.telegram_chat_id;
+
+    // Header is only sent with the first chunk; later chunks get a "(n/m)"
+    // continuation marker instead. Reserve room for the larger of the two so
+    // no chunk ever grows past the limit once it's prepended.
+    let header = format!(
+        "\u{1F4E8} {}\n<b>{}</b>\n",
+        &message.from, &message.subject
     );
+    let marker_reserve = "(999/999)\n".encode_utf16().count();
+    let reserve = header.encode_utf16().count().max(marker_reserve);
 
-    // Telegram HTML message
-    let telegram_message = format!(
-        "\u{1F4E8} {}\n<b>{}</b>\n{}",
-        &message.from,
-        &message.subject,
-        &message.body
-    );
+    let mut chunks = telegram::split_message(&message.body, telegram::MESSAGE_LIMIT.saturating_sub(reserve));
+    if chunks.is_empty() {
+        // Empty body: still send the header/subject as a standalone message
+        chunks.push("");
+    }
+    let total = chunks.len();
 
-    // Prepare JSON post data
-    let post_data = json!({
-        "chat_id": chat_id,
-        "text": telegram_message,
-        "parse_mode": "html",
-    });
-
-    // Send Message
-    match ureq::post(&telegram_message_url).send_json(post_data) {
-        Ok(response) => {
-            log::debug!("Message sent to telegram successfully");
-            log::trace!("Response: {:#?}", response);
-        },
-        Err(ureq::Error::StatusCode(code)) => {
-            log::error!("Failed to send Telegram message, error code: {}", code);
-            //log::trace!("Response: {:#?}", response);
-        },
-        Err(_) => log::error!("Failed to send Telegram message, transport error")
-    };
+    // Send Message(s)
+    for (i, chunk) in chunks.iter().enumerate() {
+        let telegram_message = if i == 0 {
+            format!("{}{}", header, chunk)
+        } else {
+            format!("({}/{})\n{}", i + 1, total, chunk)
+        };
 
-    // Send Attachments
-    for (name, content) in &message.attachments {
+        if let Err(e) = client.send_message(chat_id, &telegram_message) {
+            log::error!("Failed to send message to telegram: {}", e);
+            delivered = false;
+        }
+    }
 
-        // Get Multipart data
-        let (body, boundary) = build_multipart(name.to_string(), content.to_vec(), chat_id.to_string());
+    // Skip attachments over the configured size limit, then split the rest
+    // into images (sent as photos) and everything else (sent as documents)
+    // so each batch of up to 10 can go out as a single sendMediaGroup album.
+    let max_bytes = account.telegram_max_attachment_bytes;
+    let attachments: Vec<(&String, &Vec<u8>)> = message
+        .attachments
+        .iter()
+        .filter(|(name, content)| {
+            let oversized = content.len() as u64 > max_bytes;
+            if oversized {
+                log::warn!(
+                    "Skipping attachment {} ({} bytes): exceeds the {} byte limit",
+                    name, content.len(), max_bytes
+                );
+            }
+            !oversized
+        })
+        .map(|(name, content)| (name, content))
+        .collect();
 
-        // Send the request
-        let response = ureq::post(&telegram_document_url)
-            .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
-            .send(&body);
+    let (photos, documents): (Vec<_>, Vec<_>) =
+        attachments.into_iter().partition(|(name, _)| telegram::is_image(name));
+
+    delivered &= send_attachment_batches(&client, chat_id, &photos, "photo");
+    delivered &= send_attachment_batches(&client, chat_id, &documents, "document");
+
+    if delivered {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
 
-        // Handle response
-        match response {
-            Ok(res) => {
-                log::debug!("Sent file {}: {}", name, res.status());
+// Sends `items` to telegram in batches of up to 10: a lone attachment goes
+// out via sendPhoto/sendDocument, a batch of several via a single
+// sendMediaGroup album of the given `media_type`. Returns false if any
+// batch failed to send.
+fn send_attachment_batches(
+    client: &TelegramClient,
+    chat_id: &str,
+    items: &[(&String, &Vec<u8>)],
+    media_type: &str,
+) -> bool {
+    let mut delivered = true;
+
+    for batch in items.chunks(10) {
+        let result = match batch {
+            [(name, content)] if media_type == "photo" => client.send_photo(chat_id, name, content),
+            [(name, content)] => client.send_document(chat_id, name, content),
+            _ => {
+                let media: Vec<(&str, &[u8])> = batch
+                    .iter()
+                    .map(|(name, content)| (name.as_str(), content.as_slice()))
+                    .collect();
+                client.send_media_group(chat_id, media_type, &media)
             }
-            Err(err) => {
-                log::error!("Failed to send file {}: {}", name, err);
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to send {} attachment(s) to telegram: {}", batch.len(), e);
+            delivered = false;
+        }
+    }
+
+    delivered
+}
+
+fn find_account<'a>(accounts: &'a [Account], address: &str) -> Option<&'a Account> {
+    // Evaluate accounts in config order: first exact/glob/regex match wins
+    if let Some(account) = accounts.iter().find(|a| account_matches(a, address)) {
+        return Some(account);
+    }
+
+    // No explicit match: fall back to the configured catch-all, if any
+    let catch_all = accounts.iter().find(|a| a.catch_all);
+    if catch_all.is_none() {
+        log::warn!(
+            "[-] No account matches recipient \"{}\" and no catch-all is configured, dropping message",
+            address
+        );
+    }
+    catch_all
+}
+
+fn account_matches(account: &Account, address: &str) -> bool {
+    account.address == address
+        || account.match_patterns.iter().any(|pattern| pattern_matches(pattern, address))
+}
+
+// Matches `address` against a single pattern: `/regex/`, a glob containing
+// `*`, or a plain exact string.
+fn pattern_matches(pattern: &str, address: &str) -> bool {
+    if pattern.len() >= 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+        let inner = &pattern[1..pattern.len() - 1];
+        // Anchor the whole match: an unanchored `/example/` would otherwise
+        // also match "attacker@example.com.evil.tld", silently misrouting mail.
+        let anchored = format!("^(?:{})$", inner);
+        return match Regex::new(&anchored) {
+            Ok(re) => re.is_match(address),
+            Err(e) => {
+                log::error!("[-] Invalid regex match pattern \"{}\": {}", pattern, e);
+                false
             }
+        };
+    }
+
+    if pattern.contains('*') {
+        let anchored = format!("^{}$", regex::escape(pattern).replace(r"\*", ".*"));
+        return Regex::new(&anchored).map(|re| re.is_match(address)).unwrap_or(false);
+    }
+
+    pattern == address
+}
+
+// Polls `server` forever, fetching unseen messages, relaying each one through
+// the same Notification + send_to_telegram path the SMTP listener uses, and
+// applying `server.post_fetch_action` once it's been relayed.
+pub fn run_imap_poller(server: ImapServer, accounts: Vec<Account>) {
+    loop {
+        match poll_imap_once(&server, &accounts) {
+            Ok(0) => log::trace!("[*] No new messages on {}", server.hostname),
+            Ok(fetched) => log::info!("[+] Fetched {} message(s) from {}", fetched, server.hostname),
+            Err(e) => log::error!("[-] IMAP poll failed for {}: {}", server.hostname, e),
         }
+
+        thread::sleep(Duration::from_secs(server.poll_interval_secs));
     }
 }
 
-fn find_account<'a>(
-    accounts: &'a [Account],
-    address: &'a String,
-) -> &'a Account {
-    // Try to find the account with a matching address
-    accounts
-        .iter()
-        .find(|a| &a.address == address)
-        .or_else(|| accounts.first()).unwrap()
+fn poll_imap_once(server: &ImapServer, accounts: &[Account]) -> imap::error::Result<usize> {
+    let domain = server.hostname.as_str();
+
+    let mut session = if server.tls {
+        let tls = native_tls::TlsConnector::builder().build().unwrap();
+        let client = imap::connect((domain, server.port), domain, &tls)?;
+        client.login(&server.username, &server.password).map_err(|(e, _)| e)?
+    } else {
+        let client = imap::ClientBuilder::new(domain, server.port).connect_insecure()?;
+        client.login(&server.username, &server.password).map_err(|(e, _)| e)?
+    };
+
+    session.select(&server.mailbox)?;
+
+    // Use UID commands throughout: Delete/Move renumber sequence numbers as
+    // they go (via expunge/mv), which would point later iterations of a
+    // seq-num based loop at the wrong message. UIDs are stable for the
+    // lifetime of the mailbox.
+    let uids = session.uid_search("UNSEEN")?;
+    let mut fetched = 0;
+
+    for uid in uids {
+        // BODY.PEEK[] returns the full raw message without setting \Seen
+        let messages = session.uid_fetch(uid.to_string(), "BODY.PEEK[]")?;
+
+        // Only run the post-fetch action once every message under this uid
+        // was actually routed to an account and delivered to Telegram;
+        // otherwise leave it unseen so the next poll retries it instead of
+        // losing it to a Delete/Move.
+        let mut delivered = true;
+
+        for message in messages.iter() {
+            let Some(raw) = message.body() else { continue };
+            let Ok(mime) = std::str::from_utf8(raw) else {
+                log::error!("[-] Skipping non-UTF8 message uid={} on {}", uid, server.hostname);
+                delivered = false;
+                continue;
+            };
+
+            let notification = Notification::new(mime);
+            match find_account(accounts, &notification.to) {
+                Some(destination) => {
+                    if send_to_telegram(&notification, destination).is_err() {
+                        delivered = false;
+                    }
+                }
+                None => delivered = false,
+            }
+        }
+
+        if !delivered {
+            log::warn!(
+                "[-] Leaving uid={} unseen on {}: delivery failed, will retry next poll",
+                uid, server.hostname
+            );
+            continue;
+        }
+
+        match server.post_fetch_action {
+            PostFetchAction::MarkSeen => {
+                session.uid_store(uid.to_string(), "+FLAGS (\\Seen)")?;
+            }
+            PostFetchAction::Delete => {
+                session.uid_store(uid.to_string(), "+FLAGS (\\Deleted)")?;
+                session.expunge()?;
+            }
+            PostFetchAction::Move => match &server.move_to {
+                Some(target) => {
+                    session.uid_mv(uid.to_string(), target)?;
+                }
+                None => {
+                    log::error!("[-] post_fetch_action is move but move_to is unset, marking seen instead");
+                    session.uid_store(uid.to_string(), "+FLAGS (\\Seen)")?;
+                }
+            },
+        }
+
+        fetched += 1;
+    }
+
+    session.logout()?;
+    Ok(fetched)
 }
 
 fn collect_attachments(message: &mail_parser::Message) -> Vec<(String, Vec<u8>)> {
@@ -299,33 +627,3 @@ fn collect_attachments(message: &mail_parser::Message) -> Vec<(String, Vec<u8>)>
     attachments
 }
 
-fn build_multipart(
-    name: String, 
-    content: Vec<u8>, 
-    chat_id: String
-) -> (Vec<u8>, String) {
-    // Build multipart for file upload
-    let boundary = "------------------------boundary";
-
-    // Construct the multipart body
-    let mut body = Vec::new();
-
-    // Add the `chat_id` field
-    write!(
-        body,
-        "--{}\r\nContent-Disposition: form-data; name=\"chat_id\"\r\n\r\n{}\r\n",
-        boundary, chat_id
-    ).unwrap();
-
-    // Add the file (document)
-    write!(
-        body,
-        "--{}\r\nContent-Disposition: form-data; name=\"document\"; filename=\"{}\"\r\n\
-         Content-Type: application/octet-stream\r\n\r\n",
-        boundary, name
-    ).unwrap();
-    body.extend(content);
-    write!(body, "\r\n--{}--\r\n", boundary).unwrap();
-
-    (body, boundary.to_string())
-}
@@ -3,7 +3,7 @@ use clap::Parser;
 use log::LevelFilter;
 use mailin_embedded::{Server, SslConfig};
 
-use tginbox::{Cli, ConfigFile, MyHandler};
+use tginbox::{load_config, run_imap_poller, Cli, MyHandler};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 
@@ -12,11 +12,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse CLI arguments
     let cli = Cli::parse();
 
-    // Read configuration file
-    let configuration = {
-        let config_content = std::fs::read_to_string(&cli.config).unwrap();
-        serde_json::from_str::<ConfigFile>(&config_content)
-    }.unwrap();
+    // Read configuration file (JSON or TOML, picked by extension)
+    let configuration = load_config(&cli.config).unwrap_or_else(|e| {
+        eprintln!("[-] Failed to load config {}: {}", &cli.config, e);
+        std::process::exit(1);
+    });
 
     // Init logger
     env_logger::Builder::new()
@@ -88,6 +88,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         handles.push(handle);
     }
 
+    for imapserver in configuration.imapservers {
+        if !imapserver.enabled {
+            continue;
+        }
+
+        // Clone configuration accounts for each thread
+        let accounts = configuration.accounts.clone();
+
+        // Poll the IMAP mailbox in a separate thread
+        let handle = thread::spawn(move || {
+            log::info!(
+                "[+] Polling {}:{} mailbox \"{}\" every {}s",
+                &imapserver.hostname,
+                &imapserver.port,
+                &imapserver.mailbox,
+                &imapserver.poll_interval_secs
+            );
+
+            run_imap_poller(imapserver, accounts);
+        });
+
+        handles.push(handle);
+    }
+
     // Wait for all threads to finish
     for handle in handles {
         if let Err(e) = handle.join() {
@@ -0,0 +1,375 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use std::io::Write;
+
+use serde_derive::Deserialize;
+use serde_json::json;
+
+// Telegram's hard limit on `text` length, in UTF-16 code units
+pub const MESSAGE_LIMIT: usize = 4096;
+
+// Telegram Bot API response envelope, e.g.:
+// {"ok":true,"result":{...}}
+// {"ok":false,"error_code":429,"description":"Too Many Requests: retry after 3","parameters":{"retry_after":3}}
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    ok: bool,
+    result: Option<T>,
+    error_code: Option<i64>,
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+// The Telegram `Message` object, trimmed to the fields callers might want
+// from a successful send. Extra fields in the API response are ignored.
+#[derive(Deserialize, Debug)]
+pub struct Message {
+    pub message_id: i64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum TelegramError {
+    Api { code: Option<i64>, description: String },
+    GaveUp { attempts: u32 },
+}
+
+impl fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelegramError::Api { code, description } => {
+                write!(f, "telegram API error {}: {}", code.unwrap_or(0), description)
+            }
+            TelegramError::GaveUp { attempts } => {
+                write!(f, "gave up after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+pub type Result<T> = std::result::Result<T, TelegramError>;
+
+// A thin client over the Telegram Bot API, with bounded retries on rate
+// limits (HTTP 429, honouring `retry_after`) and transient errors
+// (5xx/transport, exponential backoff).
+pub struct TelegramClient {
+    bot_key: String,
+    max_attempts: u32,
+    backoff_base_secs: u64,
+}
+
+impl TelegramClient {
+    pub fn new(bot_key: String, max_attempts: u32, backoff_base_secs: u64) -> TelegramClient {
+        TelegramClient { bot_key, max_attempts, backoff_base_secs }
+    }
+
+    pub fn send_message(&self, chat_id: &str, text: &str) -> Result<Message> {
+        let url = self.method_url("sendMessage");
+
+        self.call("message", || {
+            let post_data = json!({
+                "chat_id": chat_id,
+                "text": text,
+                "parse_mode": "html",
+            });
+
+            ureq::post(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .send_json(post_data)
+        })
+    }
+
+    pub fn send_document(&self, chat_id: &str, name: &str, content: &[u8]) -> Result<Message> {
+        let url = self.method_url("sendDocument");
+        let (body, boundary) = build_file_multipart("document", name, content, chat_id);
+
+        self.call(&format!("file {}", name), || {
+            ureq::post(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+                .send(&body)
+        })
+    }
+
+    pub fn send_photo(&self, chat_id: &str, name: &str, content: &[u8]) -> Result<Message> {
+        let url = self.method_url("sendPhoto");
+        let (body, boundary) = build_file_multipart("photo", name, content, chat_id);
+
+        self.call(&format!("photo {}", name), || {
+            ureq::post(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+                .send(&body)
+        })
+    }
+
+    // Sends up to 10 attachments as a single album. `media_type` must be
+    // the same for every item ("photo" or "document") since Telegram
+    // doesn't allow mixing photo/video and document/audio in one group.
+    pub fn send_media_group(&self, chat_id: &str, media_type: &str, items: &[(&str, &[u8])]) -> Result<Vec<Message>> {
+        let url = self.method_url("sendMediaGroup");
+        let (body, boundary) = build_media_group_multipart(media_type, items, chat_id);
+
+        self.call(&format!("{} media group ({} items)", media_type, items.len()), || {
+            ureq::post(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+                .send(&body)
+        })
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("https://api.telegram.org/bot{}/{}", self.bot_key, method)
+    }
+
+    // Runs `attempt`, retrying on HTTP 429 (honouring `retry_after`) and on
+    // 5xx/transport errors (exponential backoff), up to `max_attempts`. Any
+    // other 4xx is returned immediately as a `TelegramError::Api`. On success,
+    // deserializes the envelope's `result` into `T`.
+    fn call<T, F>(&self, label: &str, mut attempt: F) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+        F: FnMut() -> std::result::Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    {
+        for try_n in 1..=self.max_attempts {
+            match attempt() {
+                Ok(mut response) if response.status().is_success() => {
+                    log::debug!("{} sent to telegram successfully", label);
+                    log::trace!("Response: {:#?}", response);
+
+                    let parsed: Response<T> =
+                        response.body_mut().read_json().map_err(|e| TelegramError::Api {
+                            code: None,
+                            description: format!("failed to parse telegram response: {}", e),
+                        })?;
+
+                    if !parsed.ok {
+                        return Err(TelegramError::Api {
+                            code: parsed.error_code,
+                            description: parsed.description.unwrap_or_else(|| "unknown error".to_string()),
+                        });
+                    }
+
+                    return parsed.result.ok_or_else(|| TelegramError::Api {
+                        code: None,
+                        description: "telegram response missing result".to_string(),
+                    });
+                }
+                Ok(mut response) => {
+                    let status = response.status();
+                    let error: Response<serde_json::Value> = response
+                        .body_mut()
+                        .read_json()
+                        .unwrap_or(Response { ok: false, result: None, error_code: None, description: None, parameters: None });
+
+                    let is_last_attempt = try_n == self.max_attempts;
+
+                    if status.as_u16() == 429 {
+                        let retry_after = error
+                            .parameters
+                            .as_ref()
+                            .and_then(|p| p.retry_after)
+                            .unwrap_or(self.backoff_base_secs);
+
+                        log::warn!(
+                            "Telegram rate limit hit sending {} (attempt {}/{}), retrying in {}s",
+                            label, try_n, self.max_attempts, retry_after
+                        );
+                        if !is_last_attempt {
+                            thread::sleep(Duration::from_secs(retry_after));
+                        }
+                    } else if status.is_server_error() {
+                        let wait = self.backoff_base_secs * 2u64.pow(try_n - 1);
+                        log::warn!(
+                            "Telegram server error {} sending {} (attempt {}/{}), retrying in {}s",
+                            status, label, try_n, self.max_attempts, wait
+                        );
+                        if !is_last_attempt {
+                            thread::sleep(Duration::from_secs(wait));
+                        }
+                    } else {
+                        return Err(TelegramError::Api {
+                            code: error.error_code,
+                            description: error.description.unwrap_or_else(|| status.to_string()),
+                        });
+                    }
+                }
+                Err(err) => {
+                    let wait = self.backoff_base_secs * 2u64.pow(try_n - 1);
+                    log::warn!(
+                        "Transport error sending {} (attempt {}/{}): {}, retrying in {}s",
+                        label, try_n, self.max_attempts, err, wait
+                    );
+                    if try_n != self.max_attempts {
+                        thread::sleep(Duration::from_secs(wait));
+                    }
+                }
+            }
+        }
+
+        Err(TelegramError::GaveUp { attempts: self.max_attempts })
+    }
+}
+
+// Splits `body` into pieces that fit within `limit` UTF-16 code units,
+// breaking at the nearest preceding newline or whitespace when possible,
+// and never inside an HTML tag, except a tag that starts a piece and is
+// itself longer than `limit`, which is cut.
+pub fn split_message(body: &str, limit: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut remaining = body;
+
+    while !remaining.is_empty() {
+        if remaining.encode_utf16().count() <= limit {
+            chunks.push(remaining);
+            break;
+        }
+
+        // Char index of the limit-th UTF-16 code unit
+        let mut utf16_count = 0;
+        let mut split_at = remaining.len();
+        for (idx, ch) in remaining.char_indices() {
+            utf16_count += ch.len_utf16();
+            if utf16_count > limit {
+                split_at = idx;
+                break;
+            }
+        }
+
+        // Never split inside an HTML tag opened before the cut point
+        let mut break_at = split_at;
+        if let Some(open) = remaining[..split_at].rfind('<') {
+            if remaining[open..split_at].find('>').is_none() {
+                break_at = open;
+            }
+        }
+
+        // Prefer the nearest preceding newline or whitespace over a hard cut,
+        // unless that whitespace sits inside an earlier tag
+        if let Some(ws) = remaining[..break_at].rfind(['\n', ' ']) {
+            let inside_tag = remaining[..ws]
+                .rfind('<')
+                .map_or(false, |open| remaining[open..ws].find('>').is_none());
+            if !inside_tag {
+                break_at = ws + 1;
+            }
+        }
+
+        if break_at == 0 {
+            // split_at itself can be 0 when the very first char alone
+            // already exceeds `limit`; advance to the end of that char
+            // (not a hardcoded `+1`) so the slice below lands on a valid
+            // UTF-8 boundary even for multi-byte characters.
+            let first_char_len = remaining.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            break_at = split_at.max(first_char_len);
+        }
+
+        chunks.push(remaining[..break_at].trim_end());
+        remaining = remaining[break_at..].trim_start_matches('\n');
+    }
+
+    chunks
+}
+
+// True for attachments Telegram can render inline via sendPhoto, judged by
+// the usual image extensions (no MIME type is carried through this far).
+pub fn is_image(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    ["jpg", "jpeg", "png", "gif", "webp", "bmp"]
+        .iter()
+        .any(|ext| lower.ends_with(&format!(".{}", ext)))
+}
+
+fn build_file_multipart(field: &str, name: &str, content: &[u8], chat_id: &str) -> (Vec<u8>, String) {
+    // Build multipart for a single file upload
+    let boundary = "------------------------boundary";
+
+    // Construct the multipart body
+    let mut body = Vec::new();
+
+    // Add the `chat_id` field
+    write!(
+        body,
+        "--{}\r\nContent-Disposition: form-data; name=\"chat_id\"\r\n\r\n{}\r\n",
+        boundary, chat_id
+    ).unwrap();
+
+    // Add the file
+    write!(
+        body,
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        boundary, field, name
+    ).unwrap();
+    body.extend_from_slice(content);
+    write!(body, "\r\n--{}--\r\n", boundary).unwrap();
+
+    (body, boundary.to_string())
+}
+
+// Builds a sendMediaGroup multipart request: a JSON `media` array of up to
+// 10 items referencing `attach://file{n}` parts, plus the file parts
+// themselves, all sharing `media_type` ("photo" or "document").
+fn build_media_group_multipart(
+    media_type: &str,
+    items: &[(&str, &[u8])],
+    chat_id: &str,
+) -> (Vec<u8>, String) {
+    let boundary = "------------------------boundary";
+    let mut body = Vec::new();
+
+    write!(
+        body,
+        "--{}\r\nContent-Disposition: form-data; name=\"chat_id\"\r\n\r\n{}\r\n",
+        boundary, chat_id
+    ).unwrap();
+
+    let media: Vec<serde_json::Value> = items
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| {
+            json!({
+                "type": media_type,
+                "media": format!("attach://file{}", i),
+                "caption": name,
+            })
+        })
+        .collect();
+
+    write!(
+        body,
+        "--{}\r\nContent-Disposition: form-data; name=\"media\"\r\n\r\n{}\r\n",
+        boundary,
+        serde_json::to_string(&media).unwrap()
+    ).unwrap();
+
+    for (i, (name, content)) in items.iter().enumerate() {
+        write!(
+            body,
+            "--{}\r\nContent-Disposition: form-data; name=\"file{}\"; filename=\"{}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            boundary, i, name
+        ).unwrap();
+        body.extend_from_slice(content);
+        write!(body, "\r\n").unwrap();
+    }
+
+    write!(body, "--{}--\r\n", boundary).unwrap();
+
+    (body, boundary.to_string())
+}